@@ -1,34 +1,72 @@
-#![cfg(unix)]
-use inotify::{Inotify, WatchMask};
+mod watcher;
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
 use walkdir::WalkDir;
 
-use std::collections::HashMap;
-use std::io::{self, Write};
-use std::process::Command;
+use std::collections::{HashMap, HashSet};
+#[cfg(unix)]
+use std::io;
+#[cfg(unix)]
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
 
 use confargenv::fusion;
+use watcher::{PlatformWatcher, Watcher};
 
 const CLEAR: &str = "\x1B[2J\x1B[1;1H";
+const DEFAULT_DEBOUNCE_MS: u64 = 200;
+const KILL_GRACE_PERIOD: Duration = Duration::from_millis(2000);
+const CONFIG_FILE: &str = "r.toml";
+const DEFAULT_RUST_COMMAND: &str = "cargo fmt; clear; cargo clippy --color always -q";
+const DEFAULT_MAKE_COMMAND: &str = "make -s";
+
+fn build_globset(patterns: &[String]) -> GlobSet {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        match Glob::new(pattern) {
+            Ok(glob) => {
+                builder.add(glob);
+            }
+            Err(error) => eprintln!("Invalid glob pattern {pattern}: {error}"),
+        }
+    }
+    builder
+        .build()
+        .unwrap_or_else(|_| GlobSetBuilder::new().build().expect("empty globset is valid"))
+}
 
-#[derive(Debug)]
 struct Runner {
-    inotify: Inotify,
+    watcher: Box<dyn Watcher>,
     command: String,
+    rules: Vec<Rule>,
+    debounce: Duration,
+    restart: bool,
+    children: HashMap<String, Child>,
+    include: Option<GlobSet>,
+    exclude: GlobSet,
 }
 
 impl Runner {
-    pub fn new(mode: Mode, command: Option<String>, directories: Option<Vec<String>>) -> Self {
-        let inotify = Inotify::init().expect("Error initializing inotify");
+    pub fn new(mode: Mode, config: Config, rules: Vec<Rule>) -> Self {
+        let Config {
+            command,
+            directories,
+            debounce_ms,
+            restart,
+            include,
+            exclude,
+        } = config;
 
         let (command, directories) = match mode {
             Mode::Rust => {
-                let command =
-                    command.unwrap_or("cargo fmt; clear; cargo clippy --color always -q".into());
+                let command = command.unwrap_or(DEFAULT_RUST_COMMAND.into());
                 let directories = directories.unwrap_or(vec!["src".into()]);
                 (command, directories)
             }
             Mode::Make => {
-                let command = command.unwrap_or("make -s".into());
+                let command = command.unwrap_or(DEFAULT_MAKE_COMMAND.into());
                 let directories = directories.unwrap_or(vec!["src".into(), ".".into()]);
                 (command, directories)
             }
@@ -39,57 +77,277 @@ impl Runner {
             }
         };
 
-        for directory in directories {
-            if let Err(_error) = inotify.watches().add(&directory, WatchMask::MODIFY) {
-                eprintln!("Failed to watch {directory}");
-            }
+        let mut watcher: Box<dyn Watcher> = Box::new(PlatformWatcher::new());
+        for directory in &directories {
+            watcher.watch_tree(Path::new(directory));
         }
 
-        Self { inotify, command }
+        Self {
+            watcher,
+            command,
+            rules,
+            debounce: Duration::from_millis(debounce_ms.unwrap_or(DEFAULT_DEBOUNCE_MS)),
+            restart,
+            children: HashMap::new(),
+            include: include.map(|patterns| build_globset(&patterns)),
+            exclude: build_globset(&exclude.unwrap_or_default()),
+        }
     }
 
     pub fn run(&mut self) -> ! {
         println!("{}", self.command.clone());
-        self.run_command();
+        self.run_command(&self.command.clone());
+        loop {
+            let changed = self.wait_for_settled_change();
+            for command in self.commands_for(&changed) {
+                self.run_command(&command);
+            }
+        }
+    }
+
+    /// Blocks until at least one change arrives, then keeps waiting and resetting the debounce
+    /// timer as long as new ones keep coming in, so a burst of edits (an editor's several
+    /// syscalls, a formatter touching many files) collapses into a single trigger. Returns the
+    /// paths that survived `include`/`exclude` filtering.
+    fn wait_for_settled_change(&mut self) -> Vec<PathBuf> {
+        let mut triggered: Vec<PathBuf> = self
+            .watcher
+            .wait_for_events(None)
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|path| self.path_matches(path))
+            .collect();
+
+        // The deadline only moves forward when a batch actually contains a path that
+        // survives filtering. Unmatched activity (pure directory-watch bookkeeping, or
+        // writes confined to an excluded path like a log file) still consumes `remaining`
+        // without resetting it, so noise on an excluded path can't livelock this loop.
+        let mut deadline = Instant::now() + self.debounce;
         loop {
-            // Read events that were added with `Watches::add` above.
-            let mut buffer = [0; 1024];
-            let events = self
-                .inotify
-                .read_events_blocking(&mut buffer)
-                .expect("Error while reading events");
-            for _event in events {
-                self.run_command();
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining.is_zero() {
+                return triggered;
+            }
+            let Some(paths) = self.watcher.wait_for_events(Some(remaining)) else {
+                return triggered;
+            };
+            let matched: Vec<PathBuf> = paths.into_iter().filter(|path| self.path_matches(path)).collect();
+            if !matched.is_empty() {
+                triggered.extend(matched);
+                deadline = Instant::now() + self.debounce;
             }
-            let _ = self.inotify.read_events_blocking(&mut buffer);
         }
     }
 
-    fn run_command(&self) {
+    /// A path triggers a rerun when it matches `include` (or `include` is unset) and doesn't
+    /// match `exclude`.
+    fn path_matches(&self, path: &Path) -> bool {
+        if let Some(include) = &self.include {
+            if !include.is_match(path) {
+                return false;
+            }
+        }
+        !self.exclude.is_match(path)
+    }
+
+    /// Picks the command for `path` by matching it against `rules` in order, falling back to
+    /// the top-level command when no rule matches.
+    fn command_for(&self, path: &Path) -> String {
+        for rule in &self.rules {
+            if rule.paths.is_match(path) {
+                return rule.command.clone();
+            }
+        }
+        self.command.clone()
+    }
+
+    /// Resolves every distinct command matched by `paths`, in the order their rule was first
+    /// matched, so a single debounce window that catches edits under several rules' subtrees
+    /// (a monorepo-wide `git checkout`, a global formatter run) runs each of them instead of
+    /// only the one whose path happened to arrive first. Empty `paths` means nothing survived
+    /// filtering, so this returns no commands rather than falling back to the default one.
+    fn commands_for(&self, paths: &[PathBuf]) -> Vec<String> {
+        let mut seen = HashSet::new();
+        paths
+            .iter()
+            .map(|path| self.command_for(path))
+            .filter(|command| seen.insert(command.clone()))
+            .collect()
+    }
+
+    fn run_command(&mut self, command: &str) {
+        if self.restart {
+            self.kill_previous_command(command);
+        }
+
         println!("{}", CLEAR);
-        let output = Command::new("sh")
+        let mut child_command = Command::new("sh");
+        child_command
             .arg("-c")
-            .arg(self.command.clone())
-            .output();
-        if let Ok(output) = output {
-            io::stdout().write_all(&output.stdout).unwrap();
-            io::stderr().write_all(&output.stderr).unwrap();
+            .arg(command)
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit());
+        // Put the child in its own process group so killing it also kills any shell
+        // sub-processes it spawned (e.g. a dev server started via `npm run`). Process
+        // groups are POSIX-only; on other platforms `kill_previous_command` falls back to
+        // killing just the immediate child.
+        #[cfg(unix)]
+        unsafe {
+            child_command.pre_exec(|| {
+                if libc::setpgid(0, 0) != 0 {
+                    return Err(io::Error::last_os_error());
+                }
+                Ok(())
+            });
+        }
+
+        let child = match child_command.spawn() {
+            Ok(child) => child,
+            Err(error) => {
+                eprintln!("Failed to run command: {error}");
+                return;
+            }
+        };
+
+        if self.restart {
+            self.children.insert(command.to_string(), child);
+        } else {
+            let mut child = child;
+            let _ = child.wait();
         }
     }
+
+    /// Sends SIGTERM to `command`'s previously running process group, falling back to SIGKILL
+    /// after a grace period, so a long-running child (a dev server, a test suite) doesn't
+    /// linger or pile up when edits land mid-run. Only `command`'s own child is touched, so a
+    /// debounce batch that matches several rules at once can restart each rule's process
+    /// independently instead of one rule's restart killing another's.
+    #[cfg(unix)]
+    fn kill_previous_command(&mut self, command: &str) {
+        let Some(mut child) = self.children.remove(command) else {
+            return;
+        };
+
+        let pgid = child.id() as libc::pid_t;
+        unsafe {
+            libc::kill(-pgid, libc::SIGTERM);
+        }
+
+        let deadline = Instant::now() + KILL_GRACE_PERIOD;
+        loop {
+            match child.try_wait() {
+                Ok(Some(_)) => return,
+                Ok(None) if Instant::now() >= deadline => {
+                    unsafe {
+                        libc::kill(-pgid, libc::SIGKILL);
+                    }
+                    let _ = child.wait();
+                    return;
+                }
+                Ok(None) => std::thread::sleep(Duration::from_millis(20)),
+                Err(_error) => return,
+            }
+        }
+    }
+
+    /// No process-group concept on this platform, so this only terminates `command`'s
+    /// immediate child rather than any sub-processes it spawned via the shell.
+    #[cfg(not(unix))]
+    fn kill_previous_command(&mut self, command: &str) {
+        let Some(mut child) = self.children.remove(command) else {
+            return;
+        };
+        let _ = child.kill();
+        let _ = child.wait();
+    }
 }
 
 fn main() {
     let conf = Config::new();
+    let rules = load_rules(Path::new(CONFIG_FILE));
 
-    let mode = match conf.command {
+    let mode = match &conf.command {
         None => guess_mode_by_current_directory(),
         Some(_) => Mode::Custom,
     };
 
-    let mut runner = Runner::new(mode, conf.command, conf.directories);
+    let mut runner = Runner::new(mode, conf, rules);
     runner.run();
 }
 
+/// A per-subtree command, e.g. from an `[[rule]]` table in `r.toml`: run `command` (or the
+/// default command for `mode`) when a changed path matches `paths`.
+struct Rule {
+    paths: GlobSet,
+    command: String,
+}
+
+fn default_command_for_mode(mode: Mode) -> Option<String> {
+    match mode {
+        Mode::Rust => Some(DEFAULT_RUST_COMMAND.into()),
+        Mode::Make => Some(DEFAULT_MAKE_COMMAND.into()),
+        Mode::Custom => None,
+    }
+}
+
+fn parse_mode(value: &str) -> Option<Mode> {
+    match value.to_ascii_lowercase().as_str() {
+        "rust" => Some(Mode::Rust),
+        "make" => Some(Mode::Make),
+        "custom" => Some(Mode::Custom),
+        _ => None,
+    }
+}
+
+/// Loads `[[rule]]` tables from `path` (e.g. `r.toml`), layered on top of the `fusion` defaults
+/// used for the rest of `Config`. Absent or unparsable files just mean no per-directory
+/// routing, so the flat single-command CLI behavior remains the fallback.
+fn load_rules(path: &Path) -> Vec<Rule> {
+    let Ok(contents) = std::fs::read_to_string(path) else {
+        return Vec::new();
+    };
+
+    let document: toml::Value = match contents.parse() {
+        Ok(document) => document,
+        Err(error) => {
+            eprintln!("Failed to parse {}: {error}", path.display());
+            return Vec::new();
+        }
+    };
+
+    let Some(rules) = document.get("rule").and_then(|rules| rules.as_array()) else {
+        return Vec::new();
+    };
+
+    rules
+        .iter()
+        .filter_map(|rule| {
+            let Some(paths) = rule.get("paths").and_then(|paths| paths.as_str()) else {
+                eprintln!("Skipping rule with missing or non-string `paths`: {rule:?}");
+                return None;
+            };
+            let mode = rule
+                .get("mode")
+                .and_then(|mode| mode.as_str())
+                .and_then(parse_mode);
+            let command = rule
+                .get("command")
+                .and_then(|command| command.as_str())
+                .map(String::from)
+                .or_else(|| mode.and_then(default_command_for_mode));
+            let Some(command) = command else {
+                eprintln!("Skipping rule for `{paths}`: no `command` and no valid `mode` to default from");
+                return None;
+            };
+
+            Some(Rule {
+                paths: build_globset(&[paths.to_string()]),
+                command,
+            })
+        })
+        .collect()
+}
+
 fn guess_mode_by_current_directory() -> Mode {
     let mut cargo_toml_found = false;
     let mut makefile_found = false;
@@ -127,12 +385,20 @@ enum Mode {
 struct Config {
     command: Option<String>,
     directories: Option<Vec<String>>,
+    debounce_ms: Option<u64>,
+    restart: bool,
+    include: Option<Vec<String>>,
+    exclude: Option<Vec<String>>,
 }
 impl Config {
     pub fn new() -> Self {
         let mut defaults = HashMap::new();
         defaults.insert("command", "");
         defaults.insert("directories", "");
+        defaults.insert("debounce_ms", "");
+        defaults.insert("restart", "false");
+        defaults.insert("include", "");
+        defaults.insert("exclude", "");
 
         let conf = fusion(defaults, None);
 
@@ -150,9 +416,32 @@ impl Config {
             Some(directories.split_whitespace().map(String::from).collect())
         };
 
+        let debounce_ms = conf.get("debounce_ms").unwrap();
+        let debounce_ms = debounce_ms.parse().ok();
+
+        let restart = conf.get("restart").unwrap() == "true";
+
+        let include = conf.get("include").unwrap();
+        let include = if include.is_empty() {
+            None
+        } else {
+            Some(include.split_whitespace().map(String::from).collect())
+        };
+
+        let exclude = conf.get("exclude").unwrap();
+        let exclude = if exclude.is_empty() {
+            None
+        } else {
+            Some(exclude.split_whitespace().map(String::from).collect())
+        };
+
         Self {
             command,
             directories,
+            debounce_ms,
+            restart,
+            include,
+            exclude,
         }
     }
 }
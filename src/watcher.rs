@@ -0,0 +1,240 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Abstracts over platform-specific filesystem watching backends so `Runner` can register
+/// directories and wait for changes without caring whether events come from inotify, FSEvents,
+/// kqueue, or ReadDirectoryChangesW.
+pub trait Watcher {
+    /// Registers a watch on `root` and (recursively) every subdirectory beneath it, skipping
+    /// directories excluded by `.gitignore`/`.ignore` rules and hidden directories.
+    fn watch_tree(&mut self, root: &Path);
+
+    /// Blocks for the next batch of changed paths, waiting at most `timeout` (or indefinitely
+    /// when `timeout` is `None`). Returns `None` once `timeout` elapses with nothing to report.
+    fn wait_for_events(&mut self, timeout: Option<Duration>) -> Option<Vec<PathBuf>>;
+}
+
+#[cfg(target_os = "linux")]
+pub use inotify_watcher::InotifyWatcher as PlatformWatcher;
+
+#[cfg(not(target_os = "linux"))]
+pub use notify_watcher::NotifyWatcher as PlatformWatcher;
+
+#[cfg(target_os = "linux")]
+mod inotify_watcher {
+    use super::Watcher;
+
+    use std::collections::HashMap;
+    use std::ffi::OsStr;
+    use std::os::unix::io::AsRawFd;
+    use std::path::{Path, PathBuf};
+    use std::time::Duration;
+
+    use ignore::WalkBuilder;
+    use inotify::{Event, EventMask, Inotify, WatchDescriptor, WatchMask};
+
+    fn watch_mask() -> WatchMask {
+        WatchMask::MODIFY
+            | WatchMask::CREATE
+            | WatchMask::MOVED_TO
+            | WatchMask::MOVED_FROM
+            | WatchMask::DELETE_SELF
+    }
+
+    pub struct InotifyWatcher {
+        inotify: Inotify,
+        watches: HashMap<WatchDescriptor, PathBuf>,
+    }
+
+    impl InotifyWatcher {
+        pub fn new() -> Self {
+            Self {
+                inotify: Inotify::init().expect("Error initializing inotify"),
+                watches: HashMap::new(),
+            }
+        }
+
+        fn watch_dir(&mut self, directory: &Path) {
+            match self.inotify.watches().add(directory, watch_mask()) {
+                Ok(wd) => {
+                    self.watches.insert(wd, directory.to_path_buf());
+                }
+                Err(_error) => eprintln!("Failed to watch {}", directory.display()),
+            }
+        }
+
+        fn event_path(&self, event: &Event<&OsStr>) -> Option<PathBuf> {
+            let parent = self.watches.get(&event.wd)?;
+            match &event.name {
+                Some(name) => Some(parent.join(name)),
+                None => Some(parent.clone()),
+            }
+        }
+
+        /// Applies watch bookkeeping for the event (registering new directories, dropping
+        /// removed ones) and returns the changed path when the event represents a content
+        /// change worth reporting to the caller.
+        fn handle_event(&mut self, event: &Event<&OsStr>) -> Option<PathBuf> {
+            if event.mask.contains(EventMask::ISDIR)
+                && (event.mask.contains(EventMask::CREATE)
+                    || event.mask.contains(EventMask::MOVED_TO))
+            {
+                if let (Some(parent), Some(name)) =
+                    (self.watches.get(&event.wd).cloned(), &event.name)
+                {
+                    self.watch_tree(&parent.join(name));
+                }
+                return None;
+            }
+
+            // A directory moved away reports on its parent's watch, so drop the entries at and
+            // below the moved path rather than the parent's own.
+            if event.mask.contains(EventMask::ISDIR) && event.mask.contains(EventMask::MOVED_FROM) {
+                if let (Some(parent), Some(name)) =
+                    (self.watches.get(&event.wd).cloned(), &event.name)
+                {
+                    let moved = parent.join(name);
+                    self.watches.retain(|_, path| !path.starts_with(&moved));
+                }
+                return None;
+            }
+
+            if event.mask.contains(EventMask::IGNORED)
+                || event.mask.contains(EventMask::DELETE_SELF)
+            {
+                self.watches.remove(&event.wd);
+                return None;
+            }
+
+            self.event_path(event)
+        }
+
+        /// Waits up to `timeout` for inotify's fd to become readable, then does a
+        /// non-blocking read. Returns `None` once `timeout` elapses without any new events.
+        fn poll_events(&mut self, buffer: &mut [u8], timeout: Duration) -> Option<Vec<PathBuf>> {
+            let mut fds = [libc::pollfd {
+                fd: self.inotify.as_raw_fd(),
+                events: libc::POLLIN,
+                revents: 0,
+            }];
+            let ready =
+                unsafe { libc::poll(fds.as_mut_ptr(), 1, timeout.as_millis() as libc::c_int) };
+            if ready <= 0 {
+                return None;
+            }
+            let events = self
+                .inotify
+                .read_events(buffer)
+                .expect("Error while reading events")
+                .collect::<Vec<_>>();
+            Some(events.iter().filter_map(|e| self.handle_event(e)).collect())
+        }
+    }
+
+    impl Watcher for InotifyWatcher {
+        /// Walks `root` with `ignore::WalkBuilder`, which honors `.gitignore`/`.ignore`, and
+        /// registers an inotify watch on every subdirectory found (inotify is non-recursive).
+        fn watch_tree(&mut self, root: &Path) {
+            for entry in WalkBuilder::new(root).build().filter_map(|e| e.ok()) {
+                if entry.file_type().is_some_and(|ft| ft.is_dir()) {
+                    self.watch_dir(entry.path());
+                }
+            }
+        }
+
+        fn wait_for_events(&mut self, timeout: Option<Duration>) -> Option<Vec<PathBuf>> {
+            let mut buffer = [0; 4096];
+            match timeout {
+                None => {
+                    let events = self
+                        .inotify
+                        .read_events_blocking(&mut buffer)
+                        .expect("Error while reading events")
+                        .collect::<Vec<_>>();
+                    Some(events.iter().filter_map(|e| self.handle_event(e)).collect())
+                }
+                Some(timeout) => self.poll_events(&mut buffer, timeout),
+            }
+        }
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod notify_watcher {
+    use super::Watcher;
+
+    use std::path::{Path, PathBuf};
+    use std::sync::mpsc::{self, Receiver};
+    use std::time::Duration;
+
+    use ignore::WalkBuilder;
+    use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher as _};
+
+    pub struct NotifyWatcher {
+        watcher: RecommendedWatcher,
+        events: Receiver<notify::Result<notify::Event>>,
+    }
+
+    impl NotifyWatcher {
+        pub fn new() -> Self {
+            let (tx, rx) = mpsc::channel();
+            let watcher = notify::recommended_watcher(move |event| {
+                let _ = tx.send(event);
+            })
+            .expect("Error initializing filesystem watcher");
+            Self {
+                watcher,
+                events: rx,
+            }
+        }
+
+        fn watch_dir(&mut self, directory: &Path) {
+            if let Err(error) = self.watcher.watch(directory, RecursiveMode::NonRecursive) {
+                eprintln!("Failed to watch {}: {error}", directory.display());
+            }
+        }
+    }
+
+    impl Watcher for NotifyWatcher {
+        /// Walks `root` with `ignore::WalkBuilder`, which honors `.gitignore`/`.ignore`, and
+        /// registers a non-recursive watch on every subdirectory found. This mirrors the
+        /// inotify backend instead of relying on `RecursiveMode::Recursive`, which would watch
+        /// gitignored directories (e.g. `target/`) right back and reintroduce the
+        /// cargo-rewrites-target feedback loop chunk0-4 filters out.
+        fn watch_tree(&mut self, root: &Path) {
+            for entry in WalkBuilder::new(root).build().filter_map(|e| e.ok()) {
+                if entry.file_type().is_some_and(|ft| ft.is_dir()) {
+                    self.watch_dir(entry.path());
+                }
+            }
+        }
+
+        fn wait_for_events(&mut self, timeout: Option<Duration>) -> Option<Vec<PathBuf>> {
+            let first = match timeout {
+                None => self.events.recv().ok(),
+                Some(timeout) => self.events.recv_timeout(timeout).ok(),
+            }?;
+
+            let mut raw_events = vec![first];
+            while let Ok(event) = self.events.try_recv() {
+                raw_events.push(event);
+            }
+
+            let mut paths = Vec::new();
+            for event in raw_events.into_iter().flatten() {
+                // A newly created directory needs its own watch registered (and, since
+                // `ignore` may keep walking into it, its un-ignored children too).
+                if matches!(event.kind, EventKind::Create(_)) {
+                    for path in &event.paths {
+                        if path.is_dir() {
+                            self.watch_tree(path);
+                        }
+                    }
+                }
+                paths.extend(event.paths);
+            }
+
+            Some(paths)
+        }
+    }
+}